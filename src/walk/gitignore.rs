@@ -0,0 +1,250 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+//! `.gitignore`-style pattern matching used to filter a [`WalkBuild`] traversal.
+//!
+//! [`WalkBuild`]: ../struct.WalkBuild.html
+use std::env;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std_prelude::*;
+
+use path_abs::PathDir;
+
+/// A single compiled line from a `.gitignore`-style file, along with the modifiers (`!`
+/// negation, trailing-`/` directory-only) that change how a match is interpreted.
+struct IgnorePattern {
+    glob: Glob,
+    whitelist: bool,
+    only_dir: bool,
+}
+
+/// All of the ignore patterns declared directly inside one directory.
+///
+/// One `IgnoreFrame` is pushed onto an [`IgnoreStack`] per directory depth. Patterns are kept
+/// in declaration order so that a later line can override an earlier one, exactly as `git`
+/// itself interprets a single `.gitignore` file.
+struct IgnoreFrame {
+    dir: PathDir,
+    set: GlobSet,
+    patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreFrame {
+    /// Build a frame for `dir`, reading any of `names` that exist directly inside it.
+    fn new(dir: PathDir, names: &[OsString]) -> IgnoreFrame {
+        let mut patterns = Vec::new();
+        for name in names {
+            append_patterns(&dir.join(name), &mut patterns);
+        }
+        IgnoreFrame::from_patterns(dir, patterns)
+    }
+
+    /// Build a frame from a single file, matched relative to `dir` (used for the global
+    /// gitignore file, which is not itself located in the directory it applies to).
+    fn from_file(dir: PathDir, file: &Path) -> IgnoreFrame {
+        let mut patterns = Vec::new();
+        append_patterns(file, &mut patterns);
+        IgnoreFrame::from_patterns(dir, patterns)
+    }
+
+    fn from_patterns(dir: PathDir, patterns: Vec<IgnorePattern>) -> IgnoreFrame {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &patterns {
+            builder.add(pattern.glob.clone());
+        }
+        let set = builder.build().unwrap_or_default();
+        IgnoreFrame { dir, set, patterns }
+    }
+
+    /// Return the pattern that decides the fate of `path`, if this frame has an opinion.
+    ///
+    /// `path` is matched relative to the frame's directory; when several patterns match, the
+    /// last one declared wins, mirroring how `git` reads a single `.gitignore` top to bottom.
+    fn matched(&self, path: &Path, is_dir: bool) -> Option<&IgnorePattern> {
+        let rel = path.strip_prefix(self.dir.as_path()).ok()?;
+        if rel.as_os_str().is_empty() {
+            return None;
+        }
+        self.set
+            .matches(rel)
+            .into_iter()
+            .filter(|&i| is_dir || !self.patterns[i].only_dir)
+            .max()
+            .map(|i| &self.patterns[i])
+    }
+}
+
+fn append_patterns(path: &Path, patterns: &mut Vec<IgnorePattern>) {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return,
+    };
+    for line in BufReader::new(file).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => continue,
+        };
+        if let Some(pattern) = parse_line(&line) {
+            patterns.push(pattern);
+        }
+    }
+}
+
+/// Parse one line of a `.gitignore`-style file, skipping comments and blank lines.
+fn parse_line(line: &str) -> Option<IgnorePattern> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let mut pattern = line;
+    let whitelist = match pattern.strip_prefix('!') {
+        Some(rest) => {
+            pattern = rest;
+            true
+        }
+        None => false,
+    };
+
+    let mut anchored = pattern.starts_with('/');
+    if anchored {
+        pattern = &pattern[1..];
+    }
+
+    let only_dir = pattern.len() > 1 && pattern.ends_with('/');
+    if only_dir {
+        pattern = &pattern[..pattern.len() - 1];
+    }
+    if pattern.is_empty() {
+        return None;
+    }
+
+    // A pattern with no other slash is allowed to match at any depth under the declaring
+    // directory, just like `**/pattern`. One with an internal slash is anchored to it.
+    anchored = anchored || pattern.contains('/');
+    let glob_str = if anchored {
+        pattern.to_string()
+    } else {
+        format!("**/{}", pattern)
+    };
+
+    let glob = Glob::new(&glob_str).ok()?;
+    Some(IgnorePattern {
+        glob,
+        whitelist,
+        only_dir,
+    })
+}
+
+/// The global gitignore file, as configured in `core.excludesfile` or its conventional
+/// default location. We only look at the default location: reading git's own config would
+/// require shelling out or parsing `.gitconfig`, which is more than this crate wants to take
+/// on just to find one path.
+fn global_gitignore_path() -> Option<PathBuf> {
+    if let Some(home) = env::var_os("XDG_CONFIG_HOME") {
+        let path = Path::new(&home).join("git/ignore");
+        if path.is_file() {
+            return Some(path);
+        }
+    }
+    let home = env::var_os("HOME")?;
+    let path = Path::new(&home).join(".config/git/ignore");
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// A persistent, shareable stack of [`IgnoreFrame`]s, one per directory depth, used to decide
+/// whether a candidate path should be filtered out of a [`WalkBuild`] traversal.
+///
+/// [`push`] returns a *new* `Arc<IgnoreStack>` that shares its parent's chain rather than
+/// mutating in place. This lets [`WalkParallel`] hand each queued subdirectory its own branch
+/// of the tree (and thus its own set of active ignore frames) without copying already-compiled
+/// patterns between threads; the sequential walker gets the same effect by keeping one
+/// `Arc<IgnoreStack>` per depth and truncating back to it when it leaves a directory.
+///
+/// [`WalkBuild`]: ../struct.WalkBuild.html
+/// [`WalkParallel`]: ../struct.WalkParallel.html
+/// [`push`]: #method.push
+pub(crate) struct IgnoreStack {
+    names: Arc<Vec<OsString>>,
+    frame: IgnoreFrame,
+    parent: Option<Arc<IgnoreStack>>,
+    // Only ever set on the root node; checked last, after the whole `parent` chain.
+    global: Option<Arc<IgnoreFrame>>,
+}
+
+impl IgnoreStack {
+    /// Build the root of the stack for `root`, the directory a walk starts at.
+    pub(crate) fn new(
+        root: &PathDir,
+        git_ignore: bool,
+        ignore: bool,
+        custom: &[OsString],
+    ) -> Arc<IgnoreStack> {
+        let mut names = Vec::new();
+        if git_ignore {
+            names.push(OsString::from(".gitignore"));
+        }
+        if ignore {
+            names.push(OsString::from(".ignore"));
+        }
+        names.extend(custom.iter().cloned());
+        let names = Arc::new(names);
+
+        let global = if git_ignore {
+            global_gitignore_path().map(|path| Arc::new(IgnoreFrame::from_file(root.clone(), &path)))
+        } else {
+            None
+        };
+
+        Arc::new(IgnoreStack {
+            frame: IgnoreFrame::new(root.clone(), &names),
+            names,
+            parent: None,
+            global,
+        })
+    }
+
+    /// Descend into `dir`, returning a new stack with `dir`'s own ignore files pushed on top.
+    pub(crate) fn push(self: &Arc<Self>, dir: PathDir) -> Arc<IgnoreStack> {
+        Arc::new(IgnoreStack {
+            frame: IgnoreFrame::new(dir, &self.names),
+            names: Arc::clone(&self.names),
+            parent: Some(Arc::clone(self)),
+            global: None,
+        })
+    }
+
+    /// Returns `true` if `path` should be excluded from the walk.
+    ///
+    /// Frames are checked from the most deeply nested (closest to `path`) outward; the first
+    /// frame with a matching pattern decides the outcome, so a child directory's `.gitignore`
+    /// can override (or un-ignore, via `!pattern`) a pattern set by one of its ancestors. The
+    /// global gitignore file, if any, is consulted last, as if it were an ancestor of the root.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut node = self;
+        loop {
+            if let Some(pattern) = node.frame.matched(path, is_dir) {
+                return !pattern.whitelist;
+            }
+            match node.parent {
+                Some(ref parent) => node = parent,
+                None => break,
+            }
+        }
+        if let Some(ref global) = node.global {
+            if let Some(pattern) = global.matched(path, is_dir) {
+                return !pattern.whitelist;
+            }
+        }
+        false
+    }
+}
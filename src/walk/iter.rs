@@ -0,0 +1,115 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+//! The sequential, single-threaded iterator produced by [`WalkBuild::into_iter`].
+//!
+//! [`WalkBuild::into_iter`]: ../struct.WalkBuild.html#method.into_iter
+use std_prelude::*;
+use path_abs::*;
+
+use super::gitignore::IgnoreStack;
+use super::overrides::OverrideMatcher;
+use super::{convert_walkdir_err, IgnoreConfig, OverrideConfig, PathDirEntry};
+
+type Predicate = Box<dyn FnMut(&walkdir::DirEntry) -> bool>;
+
+/// Decides, per entry, whether it should be pruned from the walk, by keeping one
+/// [`IgnoreStack`] frame per depth and truncating back to it as the walk backtracks out of a
+/// directory -- the sequential counterpart to how [`WalkParallel`] forks a fresh `Arc` chain
+/// per queued subdirectory.
+///
+/// [`WalkParallel`]: ../struct.WalkParallel.html
+struct EntryFilter {
+    ignore: Vec<Arc<IgnoreStack>>,
+    overrides: Option<OverrideMatcher>,
+}
+
+impl EntryFilter {
+    fn new(ignore: Option<Arc<IgnoreStack>>, overrides: Option<OverrideMatcher>) -> EntryFilter {
+        EntryFilter {
+            ignore: ignore.into_iter().collect(),
+            overrides,
+        }
+    }
+
+    /// Returns `false` if `entry` should be pruned: skipped if a file, not descended into if a
+    /// directory.
+    fn accept(&mut self, entry: &walkdir::DirEntry) -> bool {
+        let depth = entry.depth();
+        let is_dir = entry.file_type().is_dir();
+
+        if depth > 0 {
+            // `self.ignore` holds exactly one frame per depth already walked through; the walk
+            // root's own frame sits at index 0. Truncating back to `depth` frames before testing
+            // discards whatever siblings' subtrees left behind, leaving the frame for this
+            // entry's immediate parent directory on top.
+            self.ignore.truncate(depth);
+            let ignored = self
+                .ignore
+                .last()
+                .map(|stack| stack.is_ignored(entry.path(), is_dir))
+                .unwrap_or(false);
+            if ignored {
+                return false;
+            }
+
+            if let Some(ref matcher) = self.overrides {
+                if matcher.is_excluded(entry.path(), is_dir) {
+                    return false;
+                }
+            }
+        }
+
+        if is_dir && depth > 0 {
+            if let Some(parent) = self.ignore.last().cloned() {
+                if let Ok(abs) = PathAbs::new(entry.path()) {
+                    self.ignore.push(parent.push(PathDir::from_abs_unchecked(abs)));
+                }
+            }
+        }
+        true
+    }
+}
+
+/// A sequential iterator over a directory tree, built via [`WalkBuild::into_iter`].
+///
+/// [`WalkBuild::into_iter`]: ../struct.WalkBuild.html#method.into_iter
+pub struct WalkIter {
+    inner: walkdir::FilterEntry<walkdir::IntoIter, Predicate>,
+}
+
+impl WalkIter {
+    pub(crate) fn new(
+        path: &PathDir,
+        walk: walkdir::WalkDir,
+        ignore: IgnoreConfig,
+        overrides: OverrideConfig,
+    ) -> WalkIter {
+        let mut filter = EntryFilter::new(ignore.stack(path), overrides.matcher(path));
+        let predicate: Predicate = Box::new(move |entry| filter.accept(entry));
+        WalkIter {
+            inner: walk.into_iter().filter_entry(predicate),
+        }
+    }
+
+    /// Skip the current directory, so its contents are not yielded and it is not descended
+    /// into. Has no effect if the most recently yielded entry was not a directory.
+    pub fn skip_current_dir(&mut self) {
+        self.inner.skip_current_dir();
+    }
+}
+
+impl Iterator for WalkIter {
+    type Item = Result<PathDirEntry>;
+
+    fn next(&mut self) -> Option<Result<PathDirEntry>> {
+        self.inner.next().map(|entry| match entry {
+            Ok(entry) => PathDirEntry::new(entry),
+            Err(err) => Err(convert_walkdir_err(err)),
+        })
+    }
+}
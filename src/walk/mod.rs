@@ -5,10 +5,33 @@
  * http://opensource.org/licenses/MIT>, at your option. This file may not be
  * copied, modified, or distributed except according to those terms.
  */
+use std::ffi::OsStr;
+use std::fs::{FileType, Metadata};
+
 use std_prelude::*;
 use walkdir;
 use path_abs::*;
 
+mod gitignore;
+mod iter;
+mod overrides;
+mod parallel;
+
+use self::gitignore::IgnoreStack;
+use self::overrides::OverrideConfig;
+pub use self::iter::WalkIter;
+pub use self::parallel::{WalkParallel, WalkState};
+
+/// Convert a `walkdir::Error` into this crate's own `Error` type, so it can be handed back
+/// through the same `Result<PathDirEntry>` used for everything else in this module.
+pub(crate) fn convert_walkdir_err(err: walkdir::Error) -> Error {
+    let path = err
+        .path()
+        .map(PathArc::new)
+        .unwrap_or_else(|| PathArc::new(""));
+    Error::new(err.into(), "walking", path)
+}
+
 pub trait PathDirWalk {
     fn walk(&self) -> WalkBuild;
 }
@@ -23,6 +46,35 @@ impl PathDirWalk for PathDir {
     }
 }
 
+/// Configuration for the `.gitignore`/`.ignore`-style filtering a [`WalkBuild`] can apply.
+///
+/// [`WalkBuild`]: struct.WalkBuild.html
+#[derive(Clone, Default)]
+pub(crate) struct IgnoreConfig {
+    pub(crate) git_ignore: bool,
+    pub(crate) ignore: bool,
+    pub(crate) custom_names: Vec<OsString>,
+}
+
+impl IgnoreConfig {
+    fn is_active(&self) -> bool {
+        self.git_ignore || self.ignore || !self.custom_names.is_empty()
+    }
+
+    pub(crate) fn stack(&self, root: &PathDir) -> Option<Arc<IgnoreStack>> {
+        if self.is_active() {
+            Some(IgnoreStack::new(
+                root,
+                self.git_ignore,
+                self.ignore,
+                &self.custom_names,
+            ))
+        } else {
+            None
+        }
+    }
+}
+
 /// A builder to create an iterator for recursively walking a directory.
 ///
 /// Results are returned in depth first fashion, with directories yielded
@@ -44,7 +96,12 @@ impl PathDirWalk for PathDir {
 /// TODO: copy/paste additional docs
 pub struct WalkBuild {
     path: PathDir,
-    walk: walkdir::WalkDir
+    walk: walkdir::WalkDir,
+    ignore: IgnoreConfig,
+    overrides: OverrideConfig,
+    min_depth: usize,
+    max_depth: usize,
+    follow_links: bool,
 }
 
 impl WalkBuild {
@@ -52,6 +109,11 @@ impl WalkBuild {
         WalkBuild {
             path: path,
             walk: walk,
+            ignore: IgnoreConfig::default(),
+            overrides: OverrideConfig::default(),
+            min_depth: 0,
+            max_depth: usize::MAX,
+            follow_links: false,
         }
     }
 
@@ -61,7 +123,11 @@ impl WalkBuild {
     /// to the `new` function on this type. Its direct descendents have depth
     /// `1`, and their descendents have depth `2`, and so on.
     pub fn min_depth(self, depth: usize) -> Self {
-        WalkBuild::new(self.path, self.walk.min_depth(depth))
+        WalkBuild {
+            walk: self.walk.min_depth(depth),
+            min_depth: depth,
+            ..self
+        }
     }
 
     /// Set the maximum depth of entries yield by the iterator.
@@ -74,7 +140,11 @@ impl WalkBuild {
     /// it will actually avoid descending into directories when the depth is
     /// exceeded.
     pub fn max_depth(self, depth: usize) -> Self {
-        WalkBuild::new(self.path, self.walk.max_depth(depth))
+        WalkBuild {
+            walk: self.walk.max_depth(depth),
+            max_depth: depth,
+            ..self
+        }
     }
 
     /// Follow symbolic links. By default, this is disabled.
@@ -89,7 +159,11 @@ impl WalkBuild {
     ///
     /// [`DirEntry`]: struct.DirEntry.html
     pub fn follow_links(self, yes: bool) -> Self {
-        WalkBuild::new(self.path, self.walk.follow_links(yes))
+        WalkBuild {
+            walk: self.walk.follow_links(yes),
+            follow_links: yes,
+            ..self
+        }
     }
 
     /// Set the maximum number of simultaneously open file descriptors used
@@ -118,7 +192,10 @@ impl WalkBuild {
     /// respected. In particular, the maximum number of file descriptors opened
     /// is proportional to the depth of the directory tree traversed.
     pub fn max_open(self, n: usize) -> Self {
-        WalkBuild::new(self.path, self.walk.max_open(n))
+        WalkBuild {
+            walk: self.walk.max_open(n),
+            ..self
+        }
     }
 
     /// Set a function for sorting directory entries.
@@ -137,7 +214,10 @@ impl WalkBuild {
     pub fn sort_by<F>(self, cmp: F) -> Self
     where F: FnMut(&walkdir::DirEntry, &walkdir::DirEntry) -> Ordering + Send + Sync + 'static
     {
-        WalkBuild::new(self.path, self.walk.sort_by(cmp))
+        WalkBuild {
+            walk: self.walk.sort_by(cmp),
+            ..self
+        }
     }
 
     /// Yield a directory's contents before the directory itself. By default,
@@ -197,8 +277,104 @@ impl WalkBuild {
     /// // def
     /// // foo
     /// ```
-    pub fn contents_first(mut self, yes: bool) -> Self {
-        WalkBuild::new(self.path, self.walk.contents_first(yes))
+    pub fn contents_first(self, yes: bool) -> Self {
+        WalkBuild {
+            walk: self.walk.contents_first(yes),
+            ..self
+        }
+    }
+
+    /// Filter out paths matched by `.gitignore` files found in the walked directories, as
+    /// well as the user's global gitignore file. Disabled by default.
+    ///
+    /// A directory that is ignored is never descended into, so an ignored subtree costs
+    /// nothing beyond the single `stat` needed to notice it.
+    pub fn git_ignore(mut self, yes: bool) -> Self {
+        self.ignore.git_ignore = yes;
+        self
+    }
+
+    /// Filter out paths matched by `.ignore` files found in the walked directories. Disabled
+    /// by default.
+    ///
+    /// `.ignore` files use the same format as `.gitignore`, but are recognized by tools (such
+    /// as this one) rather than `git` itself. This is useful for ignoring paths that should be
+    /// skipped by tooling without teaching `git` to ignore them too.
+    pub fn ignore(mut self, yes: bool) -> Self {
+        self.ignore.ignore = yes;
+        self
+    }
+
+    /// Treat `name` as another ignore file, in addition to `.gitignore`/`.ignore`, when
+    /// present in a walked directory. May be called more than once.
+    pub fn add_custom_ignore_filename<S: Into<OsString>>(mut self, name: S) -> Self {
+        self.ignore.custom_names.push(name.into());
+        self
+    }
+
+    /// Restrict the walk to paths matching `globs`, relative to the walk root. Disabled (no
+    /// restriction) by default.
+    ///
+    /// A glob prefixed with `!` excludes any path it matches, taking precedence over every
+    /// other glob. Otherwise, if at least one non-`!` glob is given, only paths matching one of
+    /// them are yielded. May be called more than once; globs accumulate.
+    pub fn overrides<I: IntoIterator<Item = String>>(mut self, globs: I) -> Self {
+        self.overrides.globs.extend(globs);
+        self
+    }
+
+    /// Restrict the walk to paths matching one of the globs registered under `name`, e.g.
+    /// `"rust"` for `*.rs`. See [`define_type`] to register additional names. May be called
+    /// more than once; each call adds another type's globs to the whitelist.
+    ///
+    /// A name with no registered globs matches nothing.
+    ///
+    /// [`define_type`]: #method.define_type
+    pub fn add_type_filter<S: Into<String>>(mut self, name: S) -> Self {
+        self.overrides.type_filters.push(name.into());
+        self
+    }
+
+    /// Register `name` as shorthand for `globs`, for use with [`add_type_filter`]. Overwrites
+    /// any existing globs registered under `name`, including the built-in defaults.
+    ///
+    /// [`add_type_filter`]: #method.add_type_filter
+    pub fn define_type<S, I>(mut self, name: S, globs: I) -> Self
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = String>,
+    {
+        self.overrides.types.define(name, globs);
+        self
+    }
+
+    /// Build a multi-threaded runner for this walk.
+    ///
+    /// Unlike `WalkBuild` itself, a [`WalkParallel`] does not produce an `Iterator`: since
+    /// entries are discovered concurrently by several threads there is no single sequence to
+    /// iterate over. Instead, call [`WalkParallel::run`] with a closure that builds one visitor
+    /// closure per worker thread.
+    ///
+    /// [`WalkParallel`]: struct.WalkParallel.html
+    /// [`WalkParallel::run`]: struct.WalkParallel.html#method.run
+    pub fn walk_parallel(self) -> WalkParallel {
+        WalkParallel::new(
+            self.path,
+            self.ignore,
+            self.overrides,
+            self.min_depth,
+            self.max_depth,
+            self.follow_links,
+        )
+    }
+}
+
+impl IntoIterator for WalkBuild {
+    type Item = Result<PathDirEntry>;
+    type IntoIter = WalkIter;
+
+    fn into_iter(self) -> WalkIter {
+        WalkIter::new(&self.path, self.walk, self.ignore, self.overrides)
     }
 }
 
@@ -209,22 +385,21 @@ pub struct PathDirEntry {
 }
 
 impl PathDirEntry {
-    fn new(entry: walkdir::DirEntry) -> Result<PathDirEntry> {
-        // TODO: the file_type is already gotten, need an "unsafe" method
-        // to force-construct types
-        // let abs = PathAbs::new(entry.path())?;
-        // let ty = entry.file_type();
-        // let ty = if ty.is_file() {
-        //     PathType::File(PathFile::from_abs_unchecked(abs))
-        // } else if ty.is_dir() {
-        //     PathType::Dir(PathDir::from_abs_unchecked(abs)?)
-        // } else {
-        //     PathDir::from_abs(abs)?;
-        // };
-        Ok(PathDirEntry {
-            ty: PathType::new(entry.path())?,
-            entry: entry,
-        })
+    pub(crate) fn new(entry: walkdir::DirEntry) -> Result<PathDirEntry> {
+        // walkdir already stats every entry during traversal to get `file_type`, so we reuse
+        // it here via the unchecked constructors instead of stat-ing the path a second time
+        // via `PathType::new`. A type that's neither a file nor a directory (e.g. a broken
+        // symlink when not following links) falls back to a real stat to classify it.
+        let abs = PathAbs::new(entry.path())?;
+        let ty = entry.file_type();
+        let ty = if ty.is_file() {
+            PathType::File(PathFile::from_abs_unchecked(abs))
+        } else if ty.is_dir() {
+            PathType::Dir(PathDir::from_abs_unchecked(abs))
+        } else {
+            PathType::new(abs)?
+        };
+        Ok(PathDirEntry { ty, entry })
     }
 
     /// Convert this entry into its `PathType`
@@ -248,6 +423,27 @@ impl PathDirEntry {
     pub fn depth(&self) -> usize {
         self.entry.depth()
     }
+
+    /// Returns the file type for this entry, with no additional syscall: it was already
+    /// determined while reading the parent directory.
+    pub fn file_type(&self) -> FileType {
+        self.entry.file_type()
+    }
+
+    /// Returns the bare file name of this entry, without any leading path component.
+    pub fn file_name(&self) -> &OsStr {
+        self.entry.file_name()
+    }
+
+    /// Returns the metadata for this entry.
+    ///
+    /// This honors the [`follow_links`] setting: if enabled, this is the metadata of the
+    /// symlink's target, otherwise it's the metadata of the symlink itself.
+    ///
+    /// [`follow_links`]: struct.WalkBuild.html#method.follow_links
+    pub fn metadata(&self) -> Result<Metadata> {
+        self.entry.metadata().map_err(convert_walkdir_err)
+    }
 }
 
 impl AsRef<PathType> for PathDirEntry {
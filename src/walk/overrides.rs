@@ -0,0 +1,164 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+//! Glob-based include/exclude overrides and named file-type filters for a [`WalkBuild`].
+//!
+//! [`WalkBuild`]: ../struct.WalkBuild.html
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std_prelude::*;
+
+use path_abs::PathDir;
+
+/// The built-in name -> globs mappings available to [`WalkBuild::add_type_filter`], plus
+/// whatever a caller adds with [`WalkBuild::define_type`].
+///
+/// [`WalkBuild::add_type_filter`]: ../struct.WalkBuild.html#method.add_type_filter
+/// [`WalkBuild::define_type`]: ../struct.WalkBuild.html#method.define_type
+#[derive(Clone)]
+pub(crate) struct TypeRegistry {
+    types: HashMap<String, Vec<String>>,
+}
+
+impl Default for TypeRegistry {
+    fn default() -> TypeRegistry {
+        let mut types = HashMap::new();
+        types.insert("rust".to_string(), vec!["*.rs".to_string()]);
+        types.insert(
+            "c".to_string(),
+            vec!["*.c".to_string(), "*.h".to_string()],
+        );
+        types.insert(
+            "cpp".to_string(),
+            vec![
+                "*.c".to_string(),
+                "*.h".to_string(),
+                "*.cpp".to_string(),
+                "*.hpp".to_string(),
+            ],
+        );
+        types.insert("python".to_string(), vec!["*.py".to_string()]);
+        types.insert("go".to_string(), vec!["*.go".to_string()]);
+        types.insert("javascript".to_string(), vec!["*.js".to_string()]);
+        types.insert(
+            "markdown".to_string(),
+            vec!["*.md".to_string(), "*.markdown".to_string()],
+        );
+        types.insert("toml".to_string(), vec!["*.toml".to_string()]);
+        types.insert("json".to_string(), vec!["*.json".to_string()]);
+        types.insert(
+            "yaml".to_string(),
+            vec!["*.yml".to_string(), "*.yaml".to_string()],
+        );
+        TypeRegistry { types }
+    }
+}
+
+impl TypeRegistry {
+    /// Replace (or add) the globs associated with `name`.
+    pub(crate) fn define<S, I>(&mut self, name: S, globs: I)
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = String>,
+    {
+        self.types.insert(name.into(), globs.into_iter().collect());
+    }
+
+    fn globs(&self, name: &str) -> Option<&[String]> {
+        self.types.get(name).map(|globs| globs.as_slice())
+    }
+}
+
+/// Configuration for the glob overrides and type filters a [`WalkBuild`] can apply.
+///
+/// [`WalkBuild`]: ../struct.WalkBuild.html
+#[derive(Clone, Default)]
+pub(crate) struct OverrideConfig {
+    pub(crate) globs: Vec<String>,
+    pub(crate) type_filters: Vec<String>,
+    pub(crate) types: TypeRegistry,
+}
+
+impl OverrideConfig {
+    fn is_active(&self) -> bool {
+        !self.globs.is_empty() || !self.type_filters.is_empty()
+    }
+
+    /// Compile the configured globs and type filters into a matcher, if any were given.
+    ///
+    /// A glob that fails to parse is skipped, the same way a malformed line in a `.gitignore`
+    /// file is skipped rather than aborting the whole walk.
+    pub(crate) fn matcher(&self, root: &PathDir) -> Option<OverrideMatcher> {
+        if !self.is_active() {
+            return None;
+        }
+
+        let mut whitelist = GlobSetBuilder::new();
+        let mut blacklist = GlobSetBuilder::new();
+        let mut has_whitelist = false;
+
+        for name in &self.type_filters {
+            if let Some(globs) = self.types.globs(name) {
+                for glob in globs {
+                    if let Ok(glob) = Glob::new(glob) {
+                        whitelist.add(glob);
+                        has_whitelist = true;
+                    }
+                }
+            }
+        }
+        for raw in &self.globs {
+            let (pattern, exclude) = match raw.strip_prefix('!') {
+                Some(rest) => (rest, true),
+                None => (raw.as_str(), false),
+            };
+            let glob = match Glob::new(pattern) {
+                Ok(glob) => glob,
+                Err(_) => continue,
+            };
+            if exclude {
+                blacklist.add(glob);
+            } else {
+                whitelist.add(glob);
+                has_whitelist = true;
+            }
+        }
+
+        Some(OverrideMatcher {
+            root: root.clone(),
+            whitelist: whitelist.build().unwrap_or_default(),
+            blacklist: blacklist.build().unwrap_or_default(),
+            has_whitelist,
+        })
+    }
+}
+
+/// A compiled set of include/exclude globs, anchored at the walk root.
+pub(crate) struct OverrideMatcher {
+    root: PathDir,
+    whitelist: GlobSet,
+    blacklist: GlobSet,
+    has_whitelist: bool,
+}
+
+impl OverrideMatcher {
+    /// Returns `true` if `path` should be excluded from the walk.
+    ///
+    /// A `!`-prefixed glob always excludes on match and takes precedence. Otherwise, if at
+    /// least one whitelist glob was given, a *file* that matches none of them is excluded; a
+    /// directory is never excluded merely for not matching the whitelist, since files beneath
+    /// it still might.
+    pub(crate) fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let rel = match path.strip_prefix(self.root.as_path()) {
+            Ok(rel) => rel,
+            Err(_) => path,
+        };
+        if self.blacklist.is_match(rel) {
+            return true;
+        }
+        self.has_whitelist && !is_dir && !self.whitelist.is_match(rel)
+    }
+}
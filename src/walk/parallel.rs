@@ -0,0 +1,299 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+//! A multi-threaded directory walker, built on top of the same [`PathDirEntry`] wrapping used
+//! by the sequential walker.
+//!
+//! [`PathDirEntry`]: ../struct.PathDirEntry.html
+use std::collections::VecDeque;
+use std::sync::Condvar;
+use std::thread;
+
+use std_prelude::*;
+use path_abs::*;
+
+use super::gitignore::IgnoreStack;
+use super::overrides::{OverrideConfig, OverrideMatcher};
+use super::{convert_walkdir_err, IgnoreConfig, PathDirEntry};
+
+/// What a visitor wants to happen next after being given an entry.
+///
+/// Returned from the closure passed to [`WalkParallel::run`].
+///
+/// [`WalkParallel::run`]: struct.WalkParallel.html#method.run
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WalkState {
+    /// Keep walking as normal.
+    Continue,
+    /// If the entry is a directory, do not descend into it. Otherwise, a no-op.
+    Skip,
+    /// Stop the entire walk, on every thread, as soon as possible.
+    Quit,
+}
+
+/// A multi-threaded runner for a directory walk, built via [`WalkBuild::walk_parallel`].
+///
+/// Unlike [`WalkBuild`] (and the [`WalkIter`] it produces), `WalkParallel` has no single
+/// sequence of entries to hand back to its caller: several threads discover entries
+/// concurrently, so each thread is given its own visitor closure (built by the `mk` passed to
+/// [`run`]) to call as entries are found.
+///
+/// [`WalkBuild`]: ../struct.WalkBuild.html
+/// [`WalkBuild::walk_parallel`]: ../struct.WalkBuild.html#method.walk_parallel
+/// [`WalkIter`]: ../struct.WalkIter.html
+/// [`run`]: #method.run
+pub struct WalkParallel {
+    path: PathDir,
+    ignore: IgnoreConfig,
+    overrides: OverrideConfig,
+    min_depth: usize,
+    max_depth: usize,
+    follow_links: bool,
+    threads: usize,
+}
+
+impl WalkParallel {
+    pub(crate) fn new(
+        path: PathDir,
+        ignore: IgnoreConfig,
+        overrides: OverrideConfig,
+        min_depth: usize,
+        max_depth: usize,
+        follow_links: bool,
+    ) -> WalkParallel {
+        WalkParallel {
+            path,
+            ignore,
+            overrides,
+            min_depth,
+            max_depth,
+            follow_links,
+            threads: 0,
+        }
+    }
+
+    /// Set the number of worker threads to use. Defaults to the number of available CPUs.
+    ///
+    /// `n == 0` restores the default of querying the available parallelism at [`run`] time.
+    ///
+    /// [`run`]: #method.run
+    pub fn threads(mut self, n: usize) -> Self {
+        self.threads = n;
+        self
+    }
+
+    /// Run the walk, blocking until every entry has been visited (or a visitor returns
+    /// [`WalkState::Quit`]).
+    ///
+    /// `mk` is called once per worker thread to build that thread's own visitor closure, which
+    /// is then called once for every [`PathDirEntry`] (or error) the thread discovers. Visitor
+    /// closures run on worker threads, not the caller's thread, and must therefore be `Send`.
+    ///
+    /// [`PathDirEntry`]: ../struct.PathDirEntry.html
+    /// [`WalkState::Quit`]: enum.WalkState.html#variant.Quit
+    pub fn run<F>(self, mut mk: F)
+    where
+        F: FnMut() -> Box<dyn FnMut(Result<PathDirEntry>) -> WalkState + Send>,
+    {
+        let threads = if self.threads == 0 {
+            thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        } else {
+            self.threads
+        };
+
+        let ignore_stack = self.ignore.stack(&self.path);
+        let overrides = self.overrides.matcher(&self.path).map(Arc::new);
+        let queue = Arc::new(Queue::new(threads));
+        queue.push(WorkDir {
+            dir: self.path,
+            depth: 0,
+            ignore: ignore_stack,
+        });
+
+        let min_depth = self.min_depth;
+        let max_depth = self.max_depth;
+        let follow_links = self.follow_links;
+
+        let mut handles = Vec::with_capacity(threads);
+        for _ in 0..threads {
+            let visit = mk();
+            let queue = Arc::clone(&queue);
+            let overrides = overrides.clone();
+            handles.push(thread::spawn(move || {
+                run_worker(queue, visit, overrides, min_depth, max_depth, follow_links);
+            }));
+        }
+        for handle in handles {
+            // A panicking worker shouldn't take down the others; they'll notice the queue
+            // never drains further and wind down on their own once `quit_now` allows it.
+            let _ = handle.join();
+        }
+    }
+}
+
+/// One directory left to read, along with the depth it was found at and (if ignore filtering
+/// is enabled) the ignore frames accumulated on the path from the walk root down to it.
+struct WorkDir {
+    dir: PathDir,
+    depth: usize,
+    ignore: Option<Arc<IgnoreStack>>,
+}
+
+/// A shared queue of directories still to be read, with cooperative shutdown once every
+/// worker is idle and the queue has run dry.
+struct Queue {
+    dirs: Mutex<VecDeque<WorkDir>>,
+    cond: Condvar,
+    idle: Mutex<usize>,
+    threads: usize,
+    quit_now: AtomicBool,
+}
+
+impl Queue {
+    fn new(threads: usize) -> Queue {
+        Queue {
+            dirs: Mutex::new(VecDeque::new()),
+            cond: Condvar::new(),
+            idle: Mutex::new(0),
+            threads,
+            quit_now: AtomicBool::new(false),
+        }
+    }
+
+    fn push(&self, work: WorkDir) {
+        self.dirs.lock().unwrap().push_back(work);
+        self.cond.notify_one();
+    }
+
+    fn quit(&self) {
+        self.quit_now.store(true, AtomicOrdering::SeqCst);
+        self.cond.notify_all();
+    }
+
+    fn is_quit(&self) -> bool {
+        self.quit_now.load(AtomicOrdering::SeqCst)
+    }
+
+    /// Pop the next directory to read, or `None` once every worker has gone idle with nothing
+    /// left in the queue (or the walk was told to quit).
+    fn pop(&self) -> Option<WorkDir> {
+        let mut dirs = self.dirs.lock().unwrap();
+        loop {
+            if self.is_quit() {
+                return None;
+            }
+            if let Some(work) = dirs.pop_front() {
+                return Some(work);
+            }
+
+            let mut idle = self.idle.lock().unwrap();
+            *idle += 1;
+            if *idle == self.threads {
+                // Every worker is now idle and the queue is empty: there is no one left who
+                // could ever push more work, so the walk is done.
+                drop(idle);
+                self.quit_now.store(true, AtomicOrdering::SeqCst);
+                self.cond.notify_all();
+                return None;
+            }
+            drop(idle);
+
+            dirs = self.cond.wait(dirs).unwrap();
+            *self.idle.lock().unwrap() -= 1;
+        }
+    }
+}
+
+fn run_worker(
+    queue: Arc<Queue>,
+    mut visit: Box<dyn FnMut(Result<PathDirEntry>) -> WalkState + Send>,
+    overrides: Option<Arc<OverrideMatcher>>,
+    min_depth: usize,
+    max_depth: usize,
+    follow_links: bool,
+) {
+    while let Some(work) = queue.pop() {
+        if queue.is_quit() {
+            return;
+        }
+
+        let children = walkdir::WalkDir::new(&work.dir)
+            .min_depth(1)
+            .max_depth(1)
+            .follow_links(follow_links);
+
+        for child in children {
+            if queue.is_quit() {
+                return;
+            }
+
+            let entry = match child {
+                Ok(entry) => entry,
+                Err(err) => {
+                    if visit(Err(convert_walkdir_err(err))) == WalkState::Quit {
+                        queue.quit();
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            let depth = work.depth + 1;
+            let is_dir = entry.file_type().is_dir();
+            let ignored = work
+                .ignore
+                .as_ref()
+                .map(|stack| stack.is_ignored(entry.path(), is_dir))
+                .unwrap_or(false);
+            let excluded = overrides
+                .as_ref()
+                .map(|matcher| matcher.is_excluded(entry.path(), is_dir))
+                .unwrap_or(false);
+            if ignored || excluded {
+                // An ignored/excluded directory is never descended into; an ignored/excluded
+                // file is simply never yielded.
+                continue;
+            }
+
+            let state = if depth < min_depth {
+                WalkState::Continue
+            } else {
+                match PathDirEntry::new(entry.clone()) {
+                    Ok(path_entry) => visit(Ok(path_entry)),
+                    Err(err) => visit(Err(err)),
+                }
+            };
+
+            if state == WalkState::Quit {
+                queue.quit();
+                return;
+            }
+            if state == WalkState::Skip {
+                continue;
+            }
+            if is_dir && depth < max_depth {
+                let dir = match PathDir::new(entry.path()) {
+                    Ok(dir) => dir,
+                    Err(err) => {
+                        if visit(Err(err)) == WalkState::Quit {
+                            queue.quit();
+                            return;
+                        }
+                        continue;
+                    }
+                };
+                queue.push(WorkDir {
+                    ignore: work.ignore.as_ref().map(|stack| stack.push(dir.clone())),
+                    dir,
+                    depth,
+                });
+            }
+        }
+    }
+}